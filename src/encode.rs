@@ -0,0 +1,229 @@
+use std::io::{self, Read, Write};
+
+use brotli::{CompressorWriter, DecompressorWriter};
+use flate2::{
+    write::{GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder},
+    Compression,
+};
+use xz2::write::XzEncoder;
+use zstd::stream::write::{Decoder as ZstdDecoder, Encoder as ZstdEncoder};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Quality {
+    Fast,
+    Default,
+    Best,
+}
+
+impl Quality {
+    fn brotli_quality(self) -> u32 {
+        match self {
+            Quality::Fast => 4,
+            Quality::Default => 9,
+            Quality::Best => 11,
+        }
+    }
+
+    fn deflate_level(self) -> Compression {
+        match self {
+            Quality::Fast => Compression::fast(),
+            Quality::Default => Compression::default(),
+            Quality::Best => Compression::best(),
+        }
+    }
+
+    fn zstd_level(self) -> i32 {
+        match self {
+            Quality::Fast => 3,
+            Quality::Default => 19,
+            Quality::Best => 22,
+        }
+    }
+
+    fn xz_preset(self) -> u32 {
+        match self {
+            Quality::Fast => 3,
+            Quality::Default => 6,
+            Quality::Best => 9,
+        }
+    }
+}
+
+/// Encoder configuration (buffer size and quality) shared by all the
+/// `write_*`/`verify_*` calls for a single compression job.
+pub(crate) struct Context {
+    buffer_size: usize,
+    quality: Quality,
+}
+
+impl Context {
+    pub(crate) fn new(buffer_size: usize, quality: Quality) -> Self {
+        Context {
+            buffer_size,
+            quality,
+        }
+    }
+
+    pub(crate) fn write_brotli(
+        &mut self,
+        src: &mut impl Read,
+        dst: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut encoder =
+            CompressorWriter::new(dst, self.buffer_size, self.quality.brotli_quality(), 22);
+        io::copy(src, &mut encoder)?;
+        encoder.flush()
+    }
+
+    pub(crate) fn write_deflate(
+        &mut self,
+        src: &mut impl Read,
+        dst: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut encoder = ZlibEncoder::new(dst, self.quality.deflate_level());
+        io::copy(src, &mut encoder)?;
+        encoder.try_finish()
+    }
+
+    pub(crate) fn write_gzip(
+        &mut self,
+        src: &mut impl Read,
+        dst: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut encoder = GzEncoder::new(dst, self.quality.deflate_level());
+        io::copy(src, &mut encoder)?;
+        encoder.try_finish()
+    }
+
+    pub(crate) fn write_zstd(
+        &mut self,
+        src: &mut impl Read,
+        dst: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut encoder = ZstdEncoder::new(dst, self.quality.zstd_level())?;
+        io::copy(src, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    pub(crate) fn write_xz(
+        &mut self,
+        src: &mut impl Read,
+        dst: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut encoder = XzEncoder::new(dst, self.quality.xz_preset());
+        io::copy(src, &mut encoder)?;
+        encoder.try_finish()
+    }
+
+    /// Decodes `compressed` and checks it byte-for-byte against `original`,
+    /// without buffering either file fully in memory.
+    pub(crate) fn verify_brotli(
+        &mut self,
+        compressed: &mut impl Read,
+        original: &mut impl Read,
+    ) -> io::Result<bool> {
+        let mut sink = CompareSink::new(original);
+        {
+            let mut decoder = DecompressorWriter::new(&mut sink, self.buffer_size);
+            io::copy(compressed, &mut decoder)?;
+            decoder.flush()?;
+        }
+        sink.finish()
+    }
+
+    pub(crate) fn verify_deflate(
+        &mut self,
+        compressed: &mut impl Read,
+        original: &mut impl Read,
+    ) -> io::Result<bool> {
+        let mut sink = CompareSink::new(original);
+        {
+            let mut decoder = ZlibDecoder::new(&mut sink);
+            io::copy(compressed, &mut decoder)?;
+            decoder.try_finish()?;
+        }
+        sink.finish()
+    }
+
+    pub(crate) fn verify_gzip(
+        &mut self,
+        compressed: &mut impl Read,
+        original: &mut impl Read,
+    ) -> io::Result<bool> {
+        let mut sink = CompareSink::new(original);
+        {
+            let mut decoder = GzDecoder::new(&mut sink);
+            io::copy(compressed, &mut decoder)?;
+            decoder.try_finish()?;
+        }
+        sink.finish()
+    }
+
+    pub(crate) fn verify_zstd(
+        &mut self,
+        compressed: &mut impl Read,
+        original: &mut impl Read,
+    ) -> io::Result<bool> {
+        let mut sink = CompareSink::new(original);
+        {
+            let mut decoder = ZstdDecoder::new(&mut sink)?;
+            io::copy(compressed, &mut decoder)?;
+            decoder.flush()?;
+        }
+        sink.finish()
+    }
+}
+
+/// A `Write` sink that compares every chunk handed to it against the next
+/// bytes read from `original`, so a decoder can be driven straight through
+/// without ever materializing the decompressed (or original) file whole.
+struct CompareSink<'a, R> {
+    original: &'a mut R,
+    chunk: Box<[u8]>,
+    mismatched: bool,
+}
+
+impl<'a, R: Read> CompareSink<'a, R> {
+    fn new(original: &'a mut R) -> Self {
+        CompareSink {
+            original,
+            chunk: vec![0u8; 1 << 16].into_boxed_slice(),
+            mismatched: false,
+        }
+    }
+
+    /// Call once the decoder has finished writing: confirms there's no
+    /// trailing data left unread on the original side either.
+    fn finish(self) -> io::Result<bool> {
+        if self.mismatched {
+            return Ok(false);
+        }
+        let mut probe = [0u8; 1];
+        Ok(self.original.read(&mut probe)? == 0)
+    }
+}
+
+impl<'a, R: Read> Write for CompareSink<'a, R> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let written = buf.len();
+        while !buf.is_empty() {
+            let take = buf.len().min(self.chunk.len());
+            let expected = &mut self.chunk[..take];
+            if self.original.read_exact(expected).is_err() {
+                self.mismatched = true;
+                return Ok(written);
+            }
+            if expected != &buf[..take] {
+                self.mismatched = true;
+                return Ok(written);
+            }
+            buf = &buf[take..];
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,21 +1,24 @@
 use std::{
-    cmp::max,
+    collections::HashMap,
     fs::File,
+    io::{self, Cursor, Read},
     path::{Path, PathBuf},
-    thread::spawn,
+    sync::{Arc, Condvar, Mutex},
     time::{Duration, Instant},
 };
 
-use crossbeam::channel::{bounded, Receiver, Sender};
+use memmap2::Mmap;
+use rayon::iter::{ParallelBridge, ParallelIterator};
 
 use crate::encode::{Context, Quality};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum Algorithm {
     Brotli,
     Deflate,
     Gzip,
     Zstd,
+    Xz,
 }
 
 impl Algorithm {
@@ -25,6 +28,7 @@ impl Algorithm {
             Self::Deflate => ".zz",
             Self::Gzip => ".gz",
             Self::Zstd => ".zst",
+            Self::Xz => ".xz",
         }
     }
 }
@@ -35,17 +39,22 @@ pub(crate) struct Algorithms {
     pub(crate) deflate: bool,
     pub(crate) gzip: bool,
     pub(crate) zstd: bool,
+    pub(crate) xz: bool,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
 pub(crate) struct Stats {
     pub(crate) num_files: u64,
     pub(crate) num_errors: u64,
+    pub(crate) num_deduped: u64,
+    pub(crate) num_verify_failures: u64,
+    pub(crate) num_skipped_nogain: u64,
 
     pub(crate) brotli_time: Duration,
     pub(crate) deflate_time: Duration,
     pub(crate) gzip_time: Duration,
     pub(crate) zstd_time: Duration,
+    pub(crate) xz_time: Duration,
 }
 
 impl std::ops::Add<Stats> for Stats {
@@ -55,41 +64,113 @@ impl std::ops::Add<Stats> for Stats {
         Stats {
             num_files: self.num_files + rhs.num_files,
             num_errors: self.num_errors + rhs.num_errors,
+            num_deduped: self.num_deduped + rhs.num_deduped,
+            num_verify_failures: self.num_verify_failures + rhs.num_verify_failures,
+            num_skipped_nogain: self.num_skipped_nogain + rhs.num_skipped_nogain,
             brotli_time: self.brotli_time + rhs.brotli_time,
             deflate_time: self.deflate_time + rhs.deflate_time,
             gzip_time: self.gzip_time + rhs.gzip_time,
             zstd_time: self.zstd_time + rhs.zstd_time,
+            xz_time: self.xz_time + rhs.xz_time,
         }
     }
 }
 
 pub(crate) struct Compressor {
-    threads: usize,
-    quality: Quality,
-    algorithms: Algorithms,
+    pub(crate) threads: usize,
+    pub(crate) quality: Quality,
+    pub(crate) algorithms: Algorithms,
+    /// Round-trip decode and compare each compressed output against its
+    /// source after writing it. Covers brotli/deflate/gzip/zstd only — XZ
+    /// has no verify path yet.
+    pub(crate) verify: bool,
+    pub(crate) min_ratio: f64,
+}
+
+/// Key identifying "this exact content, compressed with this algorithm".
+type DedupKey = (blake3::Hash, Algorithm);
+
+/// Either another worker is already producing the output for a `DedupKey`,
+/// or it has finished: `Done(Some(path))` if dependents can hard-link
+/// against `path`, `Done(None)` if the producer dropped its output for
+/// insufficient gain (identical content always compresses to the same size,
+/// so dependents should drop theirs too rather than re-checking).
+enum DedupEntry {
+    InProgress(Arc<(Mutex<bool>, Condvar)>),
+    Done(Option<PathBuf>),
 }
 
-type Unit = (Algorithm, PathBuf);
+type DedupMap = Arc<Mutex<HashMap<DedupKey, DedupEntry>>>;
+
+/// One `(algorithm, source file, content hash)` job handed to the rayon pool.
+type Unit = (Algorithm, PathBuf, Option<blake3::Hash>);
+
+/// Bundles [`Compressor::process_deduped`]'s arguments, which otherwise
+/// trip `clippy::too_many_arguments`.
+struct DedupJob<'a> {
+    dedup: &'a DedupMap,
+    key: DedupKey,
+    src_path: &'a Path,
+    dst_path: &'a Path,
+    verify: bool,
+    min_ratio: f64,
+}
 
 impl Compressor {
-    pub(crate) fn new(threads: usize, quality: Quality, algorithms: Algorithms) -> Self {
+    pub(crate) fn new(
+        threads: usize,
+        quality: Quality,
+        algorithms: Algorithms,
+        verify: bool,
+        min_ratio: f64,
+    ) -> Self {
         Compressor {
             threads,
             quality,
             algorithms,
+            verify,
+            min_ratio,
         }
     }
 
-    pub(crate) fn precompress(&self, path: PathBuf) -> Stats {
-        let cap = max(self.threads * 2, 64);
-        let (tx, rx): (Sender<Unit>, Receiver<Unit>) = bounded(cap);
+    /// Like [`Compressor::precompress`], but on Linux with the `uring`
+    /// feature enabled, drives reads and writes through io_uring instead of
+    /// blocking worker threads on each syscall. Falls back to the regular
+    /// thread-pool pipeline on other targets, without the feature, if ring
+    /// setup fails at runtime, or if `verify` is set: the ring pipeline
+    /// doesn't implement round-trip verification (or dedup) yet, so running
+    /// it under `--verify` would silently skip the check instead of
+    /// performing it.
+    pub(crate) fn precompress_uring(&self, path: PathBuf) -> Stats {
+        #[cfg(all(target_os = "linux", feature = "uring"))]
+        {
+            if self.verify {
+                eprintln!(
+                    "Warning: --verify is not supported by the io_uring pipeline yet, falling back to thread pool"
+                );
+            } else {
+                match crate::uring::precompress_uring(self, path.clone()) {
+                    Ok(stats) => return stats,
+                    Err(err) => {
+                        eprintln!("Warning: io_uring pipeline failed ({}), falling back to thread pool", err);
+                    }
+                }
+            }
+        }
+        self.precompress(path)
+    }
 
+    pub(crate) fn precompress(&self, path: PathBuf) -> Stats {
         let quality = self.quality;
-        let mut handles = Vec::with_capacity(self.threads);
-        for _ in 0..self.threads {
-            let rx = rx.clone();
-            handles.push(spawn(move || Compressor::worker(rx, quality)));
-        }
+        let verify = self.verify;
+        let min_ratio = self.min_ratio;
+        let algorithms = self.algorithms;
+        let dedup: DedupMap = Arc::new(Mutex::new(HashMap::new()));
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .expect("failed to build rayon thread pool");
 
         let walk = ignore::WalkBuilder::new(&path)
             .ignore(false)
@@ -98,91 +179,392 @@ impl Compressor {
             .git_ignore(false)
             .follow_links(false)
             .build();
-        for entry in walk {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(err) => {
-                    eprintln!("Warning: {}", err);
-                    continue;
-                }
-            };
-            let path = entry.path();
-            if should_compress(path) && !path.is_symlink() && path.is_file() {
-                if self.algorithms.brotli {
-                    let path = path.to_path_buf();
-                    tx.send((Algorithm::Brotli, path)).expect("channel send");
-                }
-                if self.algorithms.deflate {
-                    let path = path.to_path_buf();
-                    tx.send((Algorithm::Deflate, path)).expect("channel send");
-                }
-                if self.algorithms.gzip {
-                    let path = path.to_path_buf();
-                    tx.send((Algorithm::Gzip, path)).expect("channel send");
-                }
-                if self.algorithms.zstd {
-                    let path = path.to_path_buf();
-                    tx.send((Algorithm::Zstd, path)).expect("channel send");
-                }
-            }
-        }
-        drop(tx);
 
-        let mut stats = Stats::default();
-        for handle in handles {
-            let h_stats = handle.join().expect("unable to join worker thread");
-            stats = stats + h_stats;
-        }
-        stats
+        pool.install(|| {
+            walk.filter_map(|entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        eprintln!("Warning: {}", err);
+                        return None;
+                    }
+                };
+                let path = entry.into_path();
+                (should_compress(&path) && !path.is_symlink() && path.is_file()).then_some(path)
+            })
+            .par_bridge()
+            .flat_map_iter(move |path| {
+                let hash = match hash_file(&path) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        eprintln!("Warning: {}: {}", path.display(), err);
+                        None
+                    }
+                };
+                units_for(algorithms)
+                    .into_iter()
+                    .map(move |alg| (alg, path.clone(), hash))
+            })
+            .map(|unit| Compressor::process_unit(quality, verify, min_ratio, &dedup, unit))
+            .reduce(Stats::default, |a, b| a + b)
+        })
     }
 
-    fn worker(rx: Receiver<Unit>, quality: Quality) -> Stats {
+    fn process_unit(quality: Quality, verify: bool, min_ratio: f64, dedup: &DedupMap, unit: Unit) -> Stats {
+        let (algorithm, pathbuf, hash) = unit;
         let mut stats = Stats::default();
         let mut ctx = Context::new(1 << 14, quality);
 
-        while let Ok((algorithm, pathbuf)) = rx.recv() {
-            let start = Instant::now();
-            if let Err(err) = Compressor::encode_file(&mut ctx, algorithm, &pathbuf) {
-                eprintln!("Warning: {}: {}", pathbuf.display(), err);
-                stats.num_errors += 1;
-            } else {
-                let dur = start.elapsed();
+        let dst_path = match dst_path_for(&pathbuf, algorithm) {
+            Some(dst_path) => dst_path,
+            None => return stats,
+        };
+
+        let result = match hash {
+            Some(hash) => Compressor::process_deduped(
+                &mut ctx,
+                &mut stats,
+                DedupJob {
+                    dedup,
+                    key: (hash, algorithm),
+                    src_path: &pathbuf,
+                    dst_path: &dst_path,
+                    verify,
+                    min_ratio,
+                },
+            ),
+            None => {
+                let start = Instant::now();
+                Compressor::encode_checked(&mut ctx, algorithm, &pathbuf, &dst_path, min_ratio).and_then(
+                    |outcome| match outcome {
+                        EncodeOutcome::DroppedNoGain => {
+                            stats.num_skipped_nogain += 1;
+                            Ok(None)
+                        }
+                        EncodeOutcome::Kept => {
+                            if verify {
+                                Compressor::check_verify(&mut ctx, algorithm, &pathbuf, &dst_path, &mut stats)?;
+                            }
+                            Ok(Some(start.elapsed()))
+                        }
+                    },
+                )
+            }
+        };
+
+        match result {
+            Ok(Some(dur)) => {
                 match algorithm {
                     Algorithm::Brotli => stats.brotli_time += dur,
                     Algorithm::Deflate => stats.deflate_time += dur,
                     Algorithm::Gzip => stats.gzip_time += dur,
                     Algorithm::Zstd => stats.zstd_time += dur,
+                    Algorithm::Xz => stats.xz_time += dur,
                 }
                 stats.num_files += 1;
             }
+            Ok(None) => {
+                // Satisfied via dedup hard-link/copy, or dropped for
+                // insufficient gain; the relevant counter was already
+                // bumped above / in process_deduped.
+            }
+            Err(err) => {
+                eprintln!("Warning: {}: {}", pathbuf.display(), err);
+                stats.num_errors += 1;
+            }
         }
 
         stats
     }
 
-    fn encode_file(ctx: &mut Context, alg: Algorithm, path: &PathBuf) -> anyhow::Result<()> {
-        let mut src = File::open(path)?;
+    /// Produces `job.dst_path`, or hard-links/copies it from a prior worker's
+    /// output for the same `(hash, algorithm)`. Returns `Ok(Some(dur))` if
+    /// this call did the compression, `Ok(None)` if it deduped or dropped
+    /// the output for insufficient gain instead.
+    fn process_deduped(
+        ctx: &mut Context,
+        stats: &mut Stats,
+        job: DedupJob,
+    ) -> anyhow::Result<Option<Duration>> {
+        let DedupJob {
+            dedup,
+            key,
+            src_path,
+            dst_path,
+            verify,
+            min_ratio,
+        } = job;
+
+        loop {
+            let wait_on = {
+                let mut map = dedup.lock().expect("dedup map poisoned");
+                match map.get(&key) {
+                    Some(DedupEntry::Done(Some(existing))) => {
+                        let existing = existing.clone();
+                        drop(map);
+                        link_or_copy(&existing, dst_path)?;
+                        stats.num_deduped += 1;
+                        return Ok(None);
+                    }
+                    Some(DedupEntry::Done(None)) => {
+                        // Identical content, same algorithm: the producer's
+                        // gain check result applies here too.
+                        stats.num_skipped_nogain += 1;
+                        return Ok(None);
+                    }
+                    Some(DedupEntry::InProgress(pair)) => Some(pair.clone()),
+                    None => {
+                        map.insert(key.clone(), DedupEntry::InProgress(Arc::new((Mutex::new(false), Condvar::new()))));
+                        None
+                    }
+                }
+            };
+
+            match wait_on {
+                Some(pair) => {
+                    let (lock, cvar) = &*pair;
+                    let mut done = lock.lock().expect("dedup wait lock poisoned");
+                    while !*done {
+                        done = cvar.wait(done).expect("dedup condvar wait");
+                    }
+                    // Loop back around: the producer has published DedupEntry::Done,
+                    // or dropped the InProgress marker after a failure (see below).
+                }
+                None => {
+                    let start = Instant::now();
+                    let outcome = Compressor::encode_checked(ctx, key.1, src_path, dst_path, min_ratio)
+                        .and_then(|outcome| match outcome {
+                            EncodeOutcome::Kept => {
+                                if verify {
+                                    Compressor::check_verify(ctx, key.1, src_path, dst_path, stats)?;
+                                }
+                                Ok(Some(dst_path.to_path_buf()))
+                            }
+                            EncodeOutcome::DroppedNoGain => {
+                                stats.num_skipped_nogain += 1;
+                                Ok(None)
+                            }
+                        });
+
+                    let mut map = dedup.lock().expect("dedup map poisoned");
+                    match outcome {
+                        Ok(done_path) => {
+                            if let Some(DedupEntry::InProgress(pair)) =
+                                map.insert(key.clone(), DedupEntry::Done(done_path.clone()))
+                            {
+                                let (lock, cvar) = &*pair;
+                                *lock.lock().expect("dedup wait lock poisoned") = true;
+                                cvar.notify_all();
+                            }
+                            drop(map);
+                            return Ok(done_path.map(|_| start.elapsed()));
+                        }
+                        Err(err) => {
+                            // Don't publish a Done state: that would conflate this
+                            // producer's failure with a valid output or a genuine
+                            // "no gain" result for every dependent with the same
+                            // content. Drop the InProgress marker instead so a
+                            // waiter retries and becomes the new producer.
+                            if let Some(DedupEntry::InProgress(pair)) = map.remove(&key) {
+                                let (lock, cvar) = &*pair;
+                                *lock.lock().expect("dedup wait lock poisoned") = true;
+                                cvar.notify_all();
+                            }
+                            drop(map);
+                            return Err(err);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decodes `dst_path` back and compares it against `src_path`, recording
+    /// a mismatch in `stats` rather than treating it as a hard error: the
+    /// compressed output still exists, it's just untrustworthy.
+    fn check_verify(
+        ctx: &mut Context,
+        alg: Algorithm,
+        src_path: &Path,
+        dst_path: &Path,
+        stats: &mut Stats,
+    ) -> anyhow::Result<()> {
+        // XZ has no verify path (yet): it wasn't part of the original
+        // brotli/zlib/gzip/zstd round-trip set.
+        let Algorithm::Brotli | Algorithm::Deflate | Algorithm::Gzip | Algorithm::Zstd = alg else {
+            return Ok(());
+        };
+
+        let mut src = File::open(src_path)?;
+        let mut compressed = File::open(dst_path)?;
+        let ok = match alg {
+            Algorithm::Brotli => ctx.verify_brotli(&mut compressed, &mut src)?,
+            Algorithm::Deflate => ctx.verify_deflate(&mut compressed, &mut src)?,
+            Algorithm::Gzip => ctx.verify_gzip(&mut compressed, &mut src)?,
+            Algorithm::Zstd => ctx.verify_zstd(&mut compressed, &mut src)?,
+            Algorithm::Xz => unreachable!(),
+        };
+        if !ok {
+            eprintln!(
+                "Warning: verify failed for {} ({:?})",
+                dst_path.display(),
+                alg
+            );
+            stats.num_verify_failures += 1;
+        }
+        Ok(())
+    }
+
+    /// Compresses `path` into `dst_path`, then deletes the output again if it
+    /// didn't shrink the file by at least `min_ratio`.
+    fn encode_checked(
+        ctx: &mut Context,
+        alg: Algorithm,
+        path: &Path,
+        dst_path: &Path,
+        min_ratio: f64,
+    ) -> anyhow::Result<EncodeOutcome> {
+        let original_len = path.metadata()?.len();
+        let written = Compressor::encode_file(ctx, alg, path, dst_path)?;
 
-        let mut file_name = match path.file_name() {
-            None => return Ok(()),
-            Some(name) => name,
+        let max_allowed = (original_len as f64 * (1.0 - min_ratio)) as u64;
+        if written > max_allowed {
+            std::fs::remove_file(dst_path)?;
+            return Ok(EncodeOutcome::DroppedNoGain);
         }
-        .to_os_string();
-        file_name.push(alg.extension());
-        let dst_path = path.with_file_name(file_name);
+        Ok(EncodeOutcome::Kept)
+    }
 
-        let mut dst = File::create(dst_path)?;
+    /// Returns the number of compressed bytes actually written, via a
+    /// counting adapter around the destination file rather than a second
+    /// `stat` call (which could race a concurrent write to the same path).
+    fn encode_file(ctx: &mut Context, alg: Algorithm, path: &Path, dst_path: &Path) -> anyhow::Result<u64> {
+        let mut src = open_source(path)?;
+        let mut dst = CountingWriter::new(File::create(dst_path)?);
         match alg {
             Algorithm::Brotli => ctx.write_brotli(&mut src, &mut dst)?,
             Algorithm::Deflate => ctx.write_deflate(&mut src, &mut dst)?,
             Algorithm::Gzip => ctx.write_gzip(&mut src, &mut dst)?,
             Algorithm::Zstd => ctx.write_zstd(&mut src, &mut dst)?,
+            Algorithm::Xz => ctx.write_xz(&mut src, &mut dst)?,
         };
-        Ok(())
+        Ok(dst.written)
+    }
+}
+
+enum EncodeOutcome {
+    Kept,
+    DroppedNoGain,
+}
+
+/// Tracks how many bytes have passed through the wrapped writer.
+struct CountingWriter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        CountingWriter { inner, written: 0 }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub(crate) fn dst_path_for(path: &Path, alg: Algorithm) -> Option<PathBuf> {
+    let mut file_name = path.file_name()?.to_os_string();
+    file_name.push(alg.extension());
+    Some(path.with_file_name(file_name))
+}
+
+pub(crate) fn units_for(algorithms: Algorithms) -> Vec<Algorithm> {
+    let mut units = Vec::with_capacity(5);
+    if algorithms.brotli {
+        units.push(Algorithm::Brotli);
+    }
+    if algorithms.deflate {
+        units.push(Algorithm::Deflate);
+    }
+    if algorithms.gzip {
+        units.push(Algorithm::Gzip);
+    }
+    if algorithms.zstd {
+        units.push(Algorithm::Zstd);
+    }
+    if algorithms.xz {
+        units.push(Algorithm::Xz);
+    }
+    units
+}
+
+/// Maps `path` into memory so encoders can read it as a single contiguous
+/// slice instead of paying a syscall per read() call. Falls back to a plain
+/// streaming `File` for zero-length files and filesystems where mmap fails.
+fn open_source(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(Box::new(file));
+    }
+    match unsafe { Mmap::map(&file) } {
+        Ok(mmap) => Ok(Box::new(Cursor::new(mmap))),
+        Err(_) => Ok(Box::new(file)),
+    }
+}
+
+/// Hashes file contents for dedup matching, or `None` for zero-length files
+/// (not worth deduping, and an empty hash would collide across unrelated
+/// empty files of different "intended" content).
+fn hash_file(path: &Path) -> std::io::Result<Option<blake3::Hash>> {
+    if path.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = [0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(Some(hasher.finalize()))
+}
+
+/// Hard-links `dst` to `src`'s already-compressed output, falling back to a
+/// plain copy when they live on different filesystems (`EXDEV`).
+fn link_or_copy(src: &Path, dst: &Path) -> std::io::Result<()> {
+    // hard_link fails with EEXIST if dst is already there (e.g. re-running
+    // over a tree with prior output), unlike the non-dedup path which
+    // overwrites via File::create. Remove it first so reruns are idempotent.
+    match std::fs::remove_file(dst) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    match std::fs::hard_link(src, dst) {
+        Ok(()) => Ok(()),
+        Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {
+            std::fs::copy(src, dst)?;
+            Ok(())
+        }
+        Err(err) => Err(err),
     }
 }
 
-fn should_compress(path: &Path) -> bool {
+pub(crate) fn should_compress(path: &Path) -> bool {
     if let Some(ext) = path.extension() {
         if let Some(ext) = ext.to_str() {
             return EXTENSIONS.contains(ext);
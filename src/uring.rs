@@ -0,0 +1,203 @@
+//! Linux io_uring pipeline, enabled by the `uring` cargo feature.
+//!
+//! Reads of source files and writes of compressed outputs are submitted as
+//! batched SQEs instead of blocking a worker thread on each syscall, mirroring
+//! how async file servers moved onto io_uring for small-file-heavy workloads.
+//! The CPU-bound compression step still runs on a rayon pool sized by
+//! `--threads`; this module only replaces the blocking read/write calls
+//! around it. Unlike the thread-pool pipeline, this one doesn't dedup
+//! identical-content files, and falls back to the thread-pool pipeline
+//! entirely when `--verify` is set (see `Compressor::precompress_uring`).
+#![cfg(all(target_os = "linux", feature = "uring"))]
+
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use tokio::sync::{oneshot, Semaphore};
+use tokio_uring::fs::File;
+
+use crate::{
+    encode::{Context, Quality},
+    precompress::{dst_path_for, should_compress, units_for, Algorithm, Compressor, Stats},
+};
+
+/// Bounds how many read→compress→write jobs (and so read/write SQEs) are in
+/// flight at once: twice the thread count, but never fewer than 64.
+fn ring_depth(threads: usize) -> usize {
+    (threads * 2).max(64)
+}
+
+pub(crate) fn precompress_uring(compressor: &Compressor, root: PathBuf) -> anyhow::Result<Stats> {
+    // `tokio_uring::start` panics if the ring can't be set up; building the
+    // runtime here returns that error so the caller can fall back instead.
+    let runtime = tokio_uring::Runtime::new(&tokio_uring::builder())?;
+    runtime.block_on(run(
+        compressor.quality,
+        compressor.algorithms,
+        compressor.min_ratio,
+        compressor.threads,
+        ring_depth(compressor.threads),
+        root,
+    ))
+}
+
+async fn run(
+    quality: Quality,
+    algorithms: crate::precompress::Algorithms,
+    min_ratio: f64,
+    threads: usize,
+    depth: usize,
+    root: PathBuf,
+) -> anyhow::Result<Stats> {
+    // Compression is CPU-bound; size this pool the same way the thread-pool
+    // pipeline does instead of farming work out to the global rayon pool, so
+    // `--threads` still bounds concurrency under `--uring`.
+    let pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("failed to build rayon thread pool"),
+    );
+    let inflight = Arc::new(Semaphore::new(depth));
+
+    let walk = ignore::WalkBuilder::new(&root)
+        .ignore(false)
+        .git_exclude(false)
+        .git_global(false)
+        .git_ignore(false)
+        .follow_links(false)
+        .build();
+
+    let mut tasks = Vec::new();
+    for entry in walk {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                eprintln!("Warning: {}", err);
+                continue;
+            }
+        };
+        let path = entry.into_path();
+        if !(should_compress(&path) && !path.is_symlink() && path.is_file()) {
+            continue;
+        }
+
+        for alg in units_for(algorithms) {
+            let permit = inflight.clone().acquire_owned().await?;
+            let path = path.clone();
+            let pool = pool.clone();
+            tasks.push(tokio_uring::spawn(async move {
+                let _permit = permit;
+                let result = encode_one(&path, alg, quality, min_ratio, &pool).await;
+                (alg, result)
+            }));
+        }
+    }
+
+    let mut stats = Stats::default();
+    for task in tasks {
+        let (alg, result) = task.await?;
+        match result {
+            Ok(Some(dur)) => {
+                match alg {
+                    Algorithm::Brotli => stats.brotli_time += dur,
+                    Algorithm::Deflate => stats.deflate_time += dur,
+                    Algorithm::Gzip => stats.gzip_time += dur,
+                    Algorithm::Zstd => stats.zstd_time += dur,
+                    Algorithm::Xz => stats.xz_time += dur,
+                }
+                stats.num_files += 1;
+            }
+            Ok(None) => {
+                stats.num_skipped_nogain += 1;
+            }
+            Err(err) => {
+                // `err` already carries the failing path via the anyhow
+                // context added in `encode_one`.
+                eprintln!("Warning: {}", err);
+                stats.num_errors += 1;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Compresses `path`, returning the elapsed time if the output was kept, or
+/// `None` if it was dropped for not shrinking the file by at least
+/// `min_ratio`.
+async fn encode_one(
+    path: &std::path::Path,
+    alg: Algorithm,
+    quality: Quality,
+    min_ratio: f64,
+    pool: &rayon::ThreadPool,
+) -> anyhow::Result<Option<std::time::Duration>> {
+    let dst_path =
+        dst_path_for(path, alg).ok_or_else(|| anyhow::anyhow!("{}: no file name", path.display()))?;
+    let start = Instant::now();
+
+    let with_path = |err: std::io::Error| anyhow::anyhow!("{}: {}", path.display(), err);
+    let src = File::open(path).await.map_err(with_path)?;
+    let data = read_all(&src).await.map_err(with_path)?;
+    src.close().await.map_err(with_path)?;
+    let original_len = data.len() as u64;
+
+    // Hand the compression step to the sized rayon pool and bridge the
+    // result back into this io_uring task with a oneshot channel.
+    let (tx, rx) = oneshot::channel();
+    pool.spawn(move || {
+        let mut ctx = Context::new(1 << 14, quality);
+        let mut src = std::io::Cursor::new(data);
+        let mut dst = Vec::new();
+        let result = match alg {
+            Algorithm::Brotli => ctx.write_brotli(&mut src, &mut dst),
+            Algorithm::Deflate => ctx.write_deflate(&mut src, &mut dst),
+            Algorithm::Gzip => ctx.write_gzip(&mut src, &mut dst),
+            Algorithm::Zstd => ctx.write_zstd(&mut src, &mut dst),
+            Algorithm::Xz => ctx.write_xz(&mut src, &mut dst),
+        };
+        let _ = tx.send(result.map(|()| dst));
+    });
+    let compressed = rx
+        .await
+        .map_err(|err| anyhow::anyhow!("{}: {}", path.display(), err))?
+        .map_err(with_path)?;
+
+    let max_allowed = (original_len as f64 * (1.0 - min_ratio)) as u64;
+    if compressed.len() as u64 > max_allowed {
+        return Ok(None);
+    }
+
+    let with_dst_path = |err: std::io::Error| anyhow::anyhow!("{}: {}", dst_path.display(), err);
+    let dst = File::create(&dst_path).await.map_err(with_dst_path)?;
+    write_all(&dst, compressed).await.map_err(with_dst_path)?;
+    dst.close().await.map_err(with_dst_path)?;
+
+    Ok(Some(start.elapsed()))
+}
+
+async fn read_all(file: &File) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut offset = 0u64;
+    loop {
+        let buf = Vec::with_capacity(1 << 16);
+        let (res, buf) = file.read_at(buf, offset).await;
+        let n = res?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+        offset += n as u64;
+    }
+    Ok(data)
+}
+
+async fn write_all(file: &File, mut data: Vec<u8>) -> std::io::Result<()> {
+    let mut offset = 0u64;
+    while !data.is_empty() {
+        let (res, buf) = file.write_at(data, offset).await;
+        let n = res?;
+        offset += n as u64;
+        data = buf[n..].to_vec();
+    }
+    Ok(())
+}